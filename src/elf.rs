@@ -0,0 +1,116 @@
+//! Minimal ELF32/ELF64 program header parsing.
+//!
+//! Only what `--elf` needs to build a UF2 image is implemented here: finding
+//! `PT_LOAD` segments and returning their physical load address plus file
+//! contents. This is not a general-purpose ELF library.
+
+const PT_LOAD: u32 = 1;
+
+/// A loadable segment extracted from an ELF file's program headers.
+pub struct Segment {
+    /// Physical address the segment should be loaded at.
+    pub paddr: u64,
+    /// Raw bytes of the segment as stored in the file (`p_filesz` long).
+    pub data: Vec<u8>,
+}
+
+/// Parses `bytes` as an ELF file and returns every `PT_LOAD` segment that has
+/// a nonzero file size, in program header order.
+///
+/// Zero-filesize segments (`.bss`-style, memory-only) are skipped since they
+/// carry no data to flash.
+pub fn load_segments(bytes: &[u8]) -> Result<Vec<Segment>, String> {
+    if bytes.len() < 20 || &bytes[0..4] != b"\x7FELF" {
+        return Err("not an ELF file".to_string());
+    }
+
+    let is_64 = match bytes[4] {
+        1 => false,
+        2 => true,
+        _ => return Err("unknown ELF class".to_string()),
+    };
+    if bytes[5] != 1 {
+        return Err("only little-endian ELF files are supported".to_string());
+    }
+
+    let mut segments = Vec::new();
+
+    if is_64 {
+        let e_phoff = read_u64(bytes, 0x20)? as usize;
+        let e_phentsize = read_u16(bytes, 0x36)? as usize;
+        let e_phnum = read_u16(bytes, 0x38)? as usize;
+
+        for i in 0..e_phnum {
+            let base = e_phoff + i * e_phentsize;
+            let p_type = read_u32(bytes, base)?;
+            if p_type != PT_LOAD {
+                continue;
+            }
+            let p_offset = read_u64(bytes, base + 0x08)? as usize;
+            let p_paddr = read_u64(bytes, base + 0x18)?;
+            let p_filesz = read_u64(bytes, base + 0x20)? as usize;
+            if p_filesz == 0 {
+                continue;
+            }
+            let data = bytes
+                .get(p_offset..p_offset + p_filesz)
+                .ok_or("segment file range is out of bounds")?
+                .to_vec();
+            segments.push(Segment {
+                paddr: p_paddr,
+                data,
+            });
+        }
+    } else {
+        let e_phoff = read_u32(bytes, 0x1C)? as usize;
+        let e_phentsize = read_u16(bytes, 0x2A)? as usize;
+        let e_phnum = read_u16(bytes, 0x2C)? as usize;
+
+        for i in 0..e_phnum {
+            let base = e_phoff + i * e_phentsize;
+            let p_type = read_u32(bytes, base)?;
+            if p_type != PT_LOAD {
+                continue;
+            }
+            let p_offset = read_u32(bytes, base + 0x04)? as usize;
+            let p_paddr = read_u32(bytes, base + 0x0C)? as u64;
+            let p_filesz = read_u32(bytes, base + 0x10)? as usize;
+            if p_filesz == 0 {
+                continue;
+            }
+            let data = bytes
+                .get(p_offset..p_offset + p_filesz)
+                .ok_or("segment file range is out of bounds")?
+                .to_vec();
+            segments.push(Segment {
+                paddr: p_paddr,
+                data,
+            });
+        }
+    }
+
+    Ok(segments)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, String> {
+    let slice = bytes
+        .get(offset..offset + 2)
+        .ok_or("ELF header field out of bounds")?;
+    Ok(u16::from_le_bytes([slice[0], slice[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    let slice = bytes
+        .get(offset..offset + 4)
+        .ok_or("ELF header field out of bounds")?;
+    Ok(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, String> {
+    let slice = bytes
+        .get(offset..offset + 8)
+        .ok_or("ELF header field out of bounds")?;
+    Ok(u64::from_le_bytes([
+        slice[0], slice[1], slice[2], slice[3], slice[4], slice[5], slice[6], slice[7],
+    ]))
+}
@@ -0,0 +1,80 @@
+//! The UF2 family ID registry.
+//!
+//! UF2's `familyID` word identifies which chip a block is meant for, so a
+//! bootloader that serves multiple boards can ignore blocks meant for
+//! someone else's. The IDs below are the well-known ones published in
+//! `uf2families.json` upstream; add more here as support for new boards is
+//! requested.
+
+/// A (name, family ID) pair in the embedded registry.
+pub struct Family {
+    pub name: &'static str,
+    pub id: u32,
+}
+
+pub const FAMILIES: &[Family] = &[
+    Family {
+        name: "RP2040",
+        id: 0xe48bff56,
+    },
+    Family {
+        name: "SAMD21",
+        id: 0x68ed2b88,
+    },
+    Family {
+        name: "SAMD51",
+        id: 0x55114460,
+    },
+    Family {
+        name: "STM32F4",
+        id: 0x57755a57,
+    },
+    Family {
+        name: "STM32F1",
+        id: 0x5ee21072,
+    },
+    Family {
+        name: "nRF52",
+        id: 0x1b57745f,
+    },
+    Family {
+        name: "ESP32",
+        id: 0x1c5f21b0,
+    },
+    Family {
+        name: "ESP32S2",
+        id: 0xbfdd4eee,
+    },
+    Family {
+        name: "ESP32S3",
+        id: 0xc47e5767,
+    },
+    Family {
+        name: "ESP8266",
+        id: 0x7eab61ed,
+    },
+];
+
+/// Resolves a `--family` argument to a family ID, accepting either a short
+/// name from the registry (case-insensitive) or a raw hex ID such as
+/// `0xe48bff56` or `e48bff56`.
+pub fn resolve(value: &str) -> Result<u32, String> {
+    if let Some(family) = FAMILIES
+        .iter()
+        .find(|family| family.name.eq_ignore_ascii_case(value))
+    {
+        return Ok(family.id);
+    }
+
+    let hex = value.strip_prefix("0x").unwrap_or(value);
+    u32::from_str_radix(hex, 16).map_err(|_| {
+        format!(
+            "unknown family '{value}' (expected a hex ID or one of: {})",
+            FAMILIES
+                .iter()
+                .map(|family| family.name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    })
+}
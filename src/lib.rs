@@ -0,0 +1,518 @@
+//! Library surface for building and inspecting UF2 files.
+//!
+//! The CLI in `main.rs` is a thin wrapper around this crate: it handles
+//! argument parsing and file I/O, while block layout, encoding, and
+//! validation all live here so they can be reused (and tested) without a
+//! process around them.
+
+use crc::crc32;
+use std::io::{Read, Write};
+
+mod crc;
+pub mod elf;
+pub mod family;
+
+/// Serializes UF2 block fields without building up an intermediate `Vec`,
+/// mirroring the `WriteBytesExt`-style extension traits used elsewhere in
+/// the Rust ecosystem for binary formats.
+pub trait BlockWriter {
+    fn write_u32_le(&mut self, value: u32) -> std::io::Result<()>;
+    fn write_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()>;
+}
+
+impl<W: Write> BlockWriter for W {
+    fn write_u32_le(&mut self, value: u32) -> std::io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.write_all(bytes)
+    }
+}
+
+#[derive(Clone)]
+struct Uf2Block {
+    magic_0: u32,
+    magic_1: u32,
+    flags: u32,
+    target_addr: u32,
+    payload_size: u32,
+    block_no: u32,
+    num_blocks: u32,
+    file_size: u32,
+    data: Vec<u8>,
+    magic_end: u32,
+}
+
+impl Uf2Block {
+    /// Builds a block. `family_id` of `None` clears the `familyID present`
+    /// flag and writes `total_size` (the real byte length of the image
+    /// being flashed) instead of a family ID, for targets that don't need
+    /// (or have) a registered family.
+    pub fn allocate(
+        target_addr: u32,
+        block_no: u32,
+        num_blocks: u32,
+        data: Vec<u8>,
+        family_id: Option<u32>,
+        total_size: u32,
+    ) -> Self {
+        return Uf2Block {
+            magic_0: 0x0A324655,
+            magic_1: 0x9E5D5157,
+            flags: match family_id {
+                Some(_) => 0x00002000, // familyID present
+                None => 0x00000000,
+            },
+            // 0x10000000 - Flash
+            // 0x20000000 - Main RAM
+            target_addr: target_addr,
+            payload_size: 256, // Per the spec, this is apparently non-negotiable
+            block_no: block_no,
+            num_blocks: num_blocks,
+            file_size: family_id.unwrap_or(total_size),
+            data: data,
+            magic_end: 0x0AB16F30,
+        };
+    }
+
+    /// Writes this block's 512 bytes through `writer`, little-endian.
+    fn write_to<W: BlockWriter>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_u32_le(self.magic_0)?;
+        writer.write_u32_le(self.magic_1)?;
+        writer.write_u32_le(self.flags)?;
+        writer.write_u32_le(self.target_addr)?;
+        writer.write_u32_le(self.payload_size)?;
+        writer.write_u32_le(self.block_no)?;
+        writer.write_u32_le(self.num_blocks)?;
+        writer.write_u32_le(self.file_size)?;
+
+        let mut data = self.data.clone();
+        data.resize(476, 0);
+        writer.write_bytes(&data)?;
+
+        writer.write_u32_le(self.magic_end)
+    }
+}
+
+pub struct Uf2 {
+    blocks: Vec<Uf2Block>,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Streams a flat binary image straight to UF2 blocks without materializing
+/// the whole output in memory: each 256-byte chunk of `bytes` becomes one
+/// block targeting `base_addr` plus its offset, tagged with `family_id`.
+///
+/// Unlike [`Uf2::create`], there's no bootrom/CRC special-casing here — this
+/// is the plain encoding path for callers that already have a flat image
+/// (e.g. linked firmware) rather than a bootrom+progdata pair.
+pub fn bin_to_uf2(
+    bytes: &[u8],
+    family_id: Option<u32>,
+    base_addr: u32,
+) -> impl Iterator<Item = [u8; 512]> + '_ {
+    let num_blocks = bytes.chunks(256).count().max(1) as u32;
+
+    bytes.chunks(256).enumerate().map(move |(index, chunk)| {
+        let mut data = chunk.to_vec();
+        data.resize(476, 0);
+
+        let block = Uf2Block::allocate(
+            base_addr.wrapping_add(index as u32 * 256),
+            index as u32,
+            num_blocks,
+            data,
+            family_id,
+            bytes.len() as u32,
+        );
+
+        let mut buf = Vec::with_capacity(512);
+        block
+            .write_to(&mut buf)
+            .expect("writing to a Vec<u8> cannot fail");
+        buf.try_into()
+            .expect("a UF2 block is always exactly 512 bytes")
+    })
+}
+
+impl Uf2 {
+    pub fn create(hex_file: &[u8], family_id: Option<u32>) -> Self {
+        let mut blocks = Vec::new();
+        let total_size = hex_file.len() as u32;
+
+        // The first chunk is special
+        let mut first_chunk = hex_file.take(252);
+        let remaining_bytes = hex_file.iter().skip(256).collect::<Vec<&u8>>();
+        let chunks = remaining_bytes.chunks(256);
+        let num_blocks = chunks.len() as u32;
+        let base_addr = 0x10000000;
+
+        // First chunk is magical and must have a crc
+        let mut buffer = Vec::new();
+        first_chunk.read_to_end(&mut buffer).ok();
+        let remaining = 252 - buffer.len();
+        // Must have 252 bytes
+        for _ in 0..remaining {
+            buffer.push(0);
+        }
+        let crc = crc32(buffer.as_slice());
+
+        // Add the crc as the last 4 bytes in little endian
+        buffer.extend_from_slice(&crc.to_le_bytes());
+        for _ in buffer.len()..476 {
+            buffer.push(0);
+        }
+        blocks.push(Uf2Block::allocate(
+            base_addr,
+            0,
+            num_blocks + 1,
+            buffer,
+            family_id,
+            total_size,
+        ));
+
+        // For each chunk, create
+        for chunk in chunks {
+            let mut data = chunk.to_vec().iter().map(|x| (**x)).collect::<Vec<u8>>();
+            for _ in data.len()..476 {
+                data.push(0);
+            }
+
+            blocks.push(Uf2Block::allocate(
+                base_addr + blocks.len() as u32 * 256,
+                blocks.len() as u32,
+                num_blocks + 1,
+                data,
+                family_id,
+                total_size,
+            ));
+        }
+
+        let block_count = blocks.len();
+        println!("{block_count} blocks generated");
+
+        return Uf2 { blocks: blocks };
+    }
+
+    /// Builds a UF2 image from ELF `PT_LOAD` segments, one run of blocks per
+    /// segment, each block's `target_addr` taken from the segment's physical
+    /// address plus its offset within the segment.
+    ///
+    /// Unlike `create`, there is no bootrom/CRC special-casing here: every
+    /// block is a plain 256-byte chunk of segment data.
+    pub fn from_elf_segments(
+        segments: Vec<elf::Segment>,
+        family_id: Option<u32>,
+    ) -> Result<Self, String> {
+        let mut blocks = Vec::new();
+        let total_chunks: usize = segments
+            .iter()
+            .map(|segment| (segment.data.len() + 255) / 256)
+            .sum();
+        let total_size: u32 = segments.iter().map(|segment| segment.data.len() as u32).sum();
+
+        for segment in &segments {
+            let base_addr: u32 = segment
+                .paddr
+                .try_into()
+                .map_err(|_| format!("segment address {:#x} is not reachable", segment.paddr))?;
+
+            for (chunk_no, chunk) in segment.data.chunks(256).enumerate() {
+                let mut data = chunk.to_vec();
+                for _ in data.len()..476 {
+                    data.push(0);
+                }
+
+                let target_addr = base_addr
+                    .checked_add(chunk_no as u32 * 256)
+                    .ok_or_else(|| format!("segment address {:#x} is not reachable", segment.paddr))?;
+
+                blocks.push(Uf2Block::allocate(
+                    target_addr,
+                    blocks.len() as u32,
+                    total_chunks as u32,
+                    data,
+                    family_id,
+                    total_size,
+                ));
+            }
+        }
+
+        Ok(Uf2 { blocks: blocks })
+    }
+
+    /// Appends file-container blocks (UF2 flag `0x00001000`) carrying
+    /// `files`, each a `(dest_path, contents)` pair, after the blocks
+    /// already in the image, then renumbers `block_no`/`num_blocks` across
+    /// the whole image.
+    ///
+    /// For a container block, `target_addr` is the byte offset within the
+    /// embedded file, the `file_size` word holds the file's total length,
+    /// and `dest_path` is written as a null-terminated UTF-8 string right
+    /// after the 256-byte payload.
+    pub fn append_embedded_files(&mut self, files: Vec<(String, Vec<u8>)>) {
+        for (dest_path, contents) in files {
+            let name_bytes = dest_path.as_bytes();
+            // Leave room for the payload plus a null terminator for the name.
+            let name_room = 476 - 256 - 1;
+            let name_len = name_bytes.len().min(name_room);
+
+            let chunks: Vec<&[u8]> = if contents.is_empty() {
+                vec![&[]]
+            } else {
+                contents.chunks(256).collect()
+            };
+
+            for (chunk_no, chunk) in chunks.iter().enumerate() {
+                let mut data = vec![0u8; 476];
+                data[..chunk.len()].copy_from_slice(chunk);
+                data[256..256 + name_len].copy_from_slice(&name_bytes[..name_len]);
+
+                self.blocks.push(Uf2Block {
+                    magic_0: 0x0A324655,
+                    magic_1: 0x9E5D5157,
+                    flags: 0x00001000, // file container
+                    target_addr: chunk_no as u32 * 256,
+                    payload_size: 256,
+                    block_no: 0,
+                    num_blocks: 0,
+                    file_size: contents.len() as u32,
+                    data,
+                    magic_end: 0x0AB16F30,
+                });
+            }
+        }
+
+        self.renumber();
+    }
+
+    fn renumber(&mut self) {
+        let total = self.blocks.len() as u32;
+        for (index, block) in self.blocks.iter_mut().enumerate() {
+            block.block_no = index as u32;
+            block.num_blocks = total;
+        }
+    }
+
+    /// Parses a UF2 file's raw bytes into its constituent blocks, checking
+    /// the structural invariants the format guarantees: magic numbers on
+    /// every block, a 256-byte payload, and a `block_no`/`num_blocks`
+    /// sequence that covers `0..num_blocks` exactly once.
+    ///
+    /// Returns one issue string per problem found rather than bailing out on
+    /// the first one, so `--verify` can report everything wrong with a file
+    /// in one pass.
+    pub fn parse(bytes: &[u8]) -> (Vec<Uf2BlockInfo>, Vec<String>) {
+        let mut blocks = Vec::new();
+        let mut issues = Vec::new();
+
+        if bytes.len() % 512 != 0 {
+            issues.push(format!(
+                "file length {} is not a multiple of the 512-byte block size",
+                bytes.len()
+            ));
+        }
+
+        for (index, raw) in bytes.chunks(512).enumerate() {
+            if raw.len() < 512 {
+                issues.push(format!("block {index} is truncated"));
+                continue;
+            }
+
+            let magic_0 = read_u32(raw, 0);
+            let magic_1 = read_u32(raw, 4);
+            let flags = read_u32(raw, 8);
+            let target_addr = read_u32(raw, 12);
+            let payload_size = read_u32(raw, 16);
+            let block_no = read_u32(raw, 20);
+            let num_blocks = read_u32(raw, 24);
+            let file_size = read_u32(raw, 28);
+            let magic_end = read_u32(raw, 508);
+
+            if magic_0 != 0x0A324655 || magic_1 != 0x9E5D5157 {
+                issues.push(format!("block {index} has an invalid start magic"));
+            }
+            if magic_end != 0x0AB16F30 {
+                issues.push(format!("block {index} has an invalid end magic"));
+            }
+            if payload_size != 256 {
+                issues.push(format!(
+                    "block {index} has payload_size {payload_size}, expected 256"
+                ));
+            }
+
+            let data = raw[32..32 + 256].to_vec();
+            blocks.push(Uf2BlockInfo {
+                flags,
+                target_addr,
+                block_no,
+                num_blocks,
+                file_size,
+                data,
+            });
+        }
+
+        if let Some(expected) = blocks.first().map(|block| block.num_blocks) {
+            // `expected` comes straight from the file; never trust it for an
+            // allocation size. The real block count (how many 512-byte
+            // chunks we actually parsed) bounds how large `seen` can be.
+            if expected as usize != blocks.len() {
+                issues.push(format!(
+                    "file claims num_blocks {}, but contains {} blocks",
+                    expected,
+                    blocks.len()
+                ));
+            }
+            let mut seen: Vec<bool> = vec![false; blocks.len()];
+            for block in &blocks {
+                if block.num_blocks != expected {
+                    issues.push(format!(
+                        "block {} claims num_blocks {}, expected {}",
+                        block.block_no, block.num_blocks, expected
+                    ));
+                    continue;
+                }
+                match seen.get_mut(block.block_no as usize) {
+                    Some(slot) if !*slot => *slot = true,
+                    Some(_) => issues.push(format!("block_no {} appears more than once", block.block_no)),
+                    None => issues.push(format!(
+                        "block_no {} is out of range for the {} blocks in the file",
+                        block.block_no,
+                        blocks.len()
+                    )),
+                }
+            }
+            for (block_no, present) in seen.iter().enumerate() {
+                if !present {
+                    issues.push(format!("block_no {block_no} is missing"));
+                }
+            }
+        }
+
+        // Sort the blocks by target_addr and flag any 256-byte address range
+        // that overlaps the block before it. Only addresses are compared; no
+        // flat image is built from the payloads. File-container blocks (flag
+        // 0x1000) use target_addr as a byte offset within the embedded file
+        // rather than a memory address, so they are excluded here.
+        let mut by_addr: Vec<(u32, u32)> = blocks
+            .iter()
+            .filter(|block| block.flags & 0x1000 == 0)
+            .map(|block| (block.target_addr, block.block_no))
+            .collect();
+        by_addr.sort_by_key(|(addr, _)| *addr);
+        for window in by_addr.windows(2) {
+            let (prev_addr, prev_block) = window[0];
+            let (next_addr, next_block) = window[1];
+            if next_addr < prev_addr.saturating_add(256) {
+                issues.push(format!(
+                    "block {next_block} at {next_addr:#x} overlaps block {prev_block} at {prev_addr:#x}"
+                ));
+            }
+        }
+
+        (blocks, issues)
+    }
+
+    /// Re-derives the RP2040 bootrom CRC for `blocks[0]` and reports a
+    /// mismatch against the CRC embedded in bytes 252..256 of its payload.
+    pub fn verify_rp2040_crc(blocks: &[Uf2BlockInfo]) -> Option<String> {
+        let first = blocks.first()?;
+        if first.data.len() < 256 {
+            return Some("first block is too short to contain an RP2040 CRC".to_string());
+        }
+
+        let expected = crc32(&first.data[0..252]);
+        let stored = u32::from_le_bytes([
+            first.data[252],
+            first.data[253],
+            first.data[254],
+            first.data[255],
+        ]);
+
+        if expected != stored {
+            Some(format!(
+                "RP2040 CRC mismatch: computed {expected:#010x}, stored {stored:#010x}"
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Streams every block in this image to `writer`, 512 bytes at a time,
+    /// instead of collecting them into one large `Vec` first.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        for block in &self.blocks {
+            block.write_to(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// A parsed block's fields, as returned by [`Uf2::parse`].
+pub struct Uf2BlockInfo {
+    pub flags: u32,
+    pub target_addr: u32,
+    pub block_no: u32,
+    pub num_blocks: u32,
+    pub file_size: u32,
+    pub data: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bin_to_uf2_encodes_a_single_block() {
+        let data = [1u8, 2, 3, 4];
+        let blocks: Vec<[u8; 512]> = bin_to_uf2(&data, Some(0xe48bff56), 0x10000000).collect();
+
+        assert_eq!(blocks.len(), 1);
+        let block = &blocks[0];
+
+        assert_eq!(read_u32(block, 0), 0x0A324655); // magic_0
+        assert_eq!(read_u32(block, 4), 0x9E5D5157); // magic_1
+        assert_eq!(read_u32(block, 8), 0x00002000); // flags: familyID present
+        assert_eq!(read_u32(block, 12), 0x10000000); // target_addr
+        assert_eq!(read_u32(block, 16), 256); // payload_size
+        assert_eq!(read_u32(block, 20), 0); // block_no
+        assert_eq!(read_u32(block, 24), 1); // num_blocks
+        assert_eq!(read_u32(block, 28), 0xe48bff56); // familyID
+        assert_eq!(&block[32..36], &[1, 2, 3, 4]);
+        assert_eq!(&block[36..32 + 476], &vec![0u8; 476 - 4][..]);
+        assert_eq!(read_u32(block, 508), 0x0AB16F30); // magic_end
+    }
+
+    #[test]
+    fn bin_to_uf2_without_a_family_clears_the_flag() {
+        let data = [0u8; 4];
+        let blocks: Vec<[u8; 512]> = bin_to_uf2(&data, None, 0x20000000).collect();
+
+        assert_eq!(read_u32(&blocks[0], 8), 0); // flags
+        assert_eq!(read_u32(&blocks[0], 28), data.len() as u32); // plain file_size
+    }
+
+    #[test]
+    fn parse_round_trips_bin_to_uf2_output() {
+        let data: Vec<u8> = (0..300).map(|n| n as u8).collect();
+        let mut bytes = Vec::new();
+        for block in bin_to_uf2(&data, Some(0x68ed2b88), 0) {
+            bytes.extend_from_slice(&block);
+        }
+
+        let (blocks, issues) = Uf2::parse(&bytes);
+        assert!(issues.is_empty(), "unexpected issues: {issues:?}");
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].target_addr, 0);
+        assert_eq!(blocks[1].target_addr, 256);
+    }
+}
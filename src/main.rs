@@ -1,11 +1,9 @@
 #![allow(unused)]
 
 use clap::Parser;
-use crc::crc32;
 use std::fs::File;
 use std::io::prelude::*;
-
-mod crc;
+use uf2_util::{elf, family, Uf2};
 
 /// A utility to help generate uf2 files which can be flashed to an
 /// rp2040 microcontroller.
@@ -14,162 +12,148 @@ mod crc;
 struct Args {
     /// The bootrom binary file that you want to flash to the pico
     /// which should not exceed 252 bytes.
-    #[arg(short, long)]
-    bootrom: String,
+    ///
+    /// Ignored when `--elf` is given.
+    #[arg(short, long, required_unless_present_any = ["elf", "verify"])]
+    bootrom: Option<String>,
 
     /// The program binary file that you want to flash to the pico
     /// which will be placed in memory 256-byte aligned.
-    #[arg(short, long)]
-    progdata: String,
+    ///
+    /// Ignored when `--elf` is given.
+    #[arg(short, long, required_unless_present_any = ["elf", "verify"])]
+    progdata: Option<String>,
+
+    /// An ELF file to convert instead of a raw bootrom/progdata pair.
+    ///
+    /// Each `PT_LOAD` program header with a nonzero file size becomes a
+    /// series of UF2 blocks targeting that segment's physical address,
+    /// rather than the fixed `0x10000000` flash base used by the raw mode.
+    #[arg(long)]
+    elf: Option<String>,
+
+    /// The target chip family, as a short name (e.g. `RP2040`, `SAMD21`,
+    /// `STM32F4`, `nRF52`) or a raw hex family ID (e.g. `0xe48bff56`).
+    ///
+    /// When omitted, blocks are written without a familyID and the
+    /// `familyID present` flag is left unset.
+    #[arg(long)]
+    family: Option<String>,
+
+    /// Validate an existing UF2 file instead of generating one: checks
+    /// magic numbers, a contiguous block_no/num_blocks sequence, and (for
+    /// RP2040 images) the bootrom CRC, then prints any problems found.
+    #[arg(long)]
+    verify: Option<String>,
+
+    /// Embed an arbitrary file into the UF2 as a file-container block run,
+    /// in `dest_path=src_file` form (repeatable). `dest_path` is written
+    /// onto the device's mass-storage filesystem; `src_file` is read from
+    /// disk here and chunked the same way firmware data is.
+    #[arg(long, value_name = "DEST_PATH=SRC_FILE")]
+    embed: Vec<String>,
 
     /// The output file name
-    #[arg(short, long)]
-    output: String,
-}
-
-#[derive(Clone)]
-struct Uf2Block {
-    magic_0: u32,
-    magic_1: u32,
-    flags: u32,
-    target_addr: u32,
-    payload_size: u32,
-    block_no: u32,
-    num_blocks: u32,
-    file_size: u32,
-    data: Vec<u8>,
-    magic_end: u32,
-}
-
-impl Uf2Block {
-    pub fn allocate(target_addr: u32, block_no: u32, num_blocks: u32, data: Vec<u8>) -> Self {
-        return Uf2Block {
-            magic_0: 0x0A324655,
-            magic_1: 0x9E5D5157,
-            flags: 0x00002000, // familyID present
-            // 0x10000000 - Flash
-            // 0x20000000 - Main RAM
-            target_addr: target_addr,
-            payload_size: 256, // Per the spec, this is apparently non-negotiable
-            block_no: block_no,
-            num_blocks: num_blocks,
-            file_size: 0xe48bff56, // Family ID for RP2040
-            data: data,
-            magic_end: 0x0AB16F30,
-        };
-    }
-}
-
-struct Uf2 {
-    blocks: Vec<Uf2Block>,
+    #[arg(short, long, required_unless_present = "verify")]
+    output: Option<String>,
 }
 
-fn write_little_endian(vec: &mut Vec<u8>, block: u32) {
-    vec.push((block & 0xFF) as u8);
-    vec.push(((block & 0xFF00) >> 8) as u8);
-    vec.push(((block & 0xFF0000) >> 16) as u8);
-    vec.push((block >> 24) as u8);
-}
-
-impl Uf2 {
-    pub fn create(hex_file: &[u8]) -> Self {
-        let mut blocks = Vec::new();
-
-        // The first chunk is special
-        let mut first_chunk = hex_file.take(252);
-        let remaining_bytes = hex_file.iter().skip(256).collect::<Vec<&u8>>();
-        let chunks = remaining_bytes.chunks(256);
-        let num_blocks = chunks.len() as u32;
-        let base_addr = 0x10000000;
-
-        // First chunk is magical and must have a crc
-        let mut buffer = Vec::new();
-        first_chunk.read_to_end(&mut buffer);
-        let remaining = 252 - buffer.len();
-        // Must have 252 bytes
-        for _ in 0..remaining {
-            buffer.push(0);
-        }
-        let crc = crc32(buffer.as_slice());
-
-        // Add the crc as the last 4 bytes in little endian
-        write_little_endian(&mut buffer, crc);
-        for _ in buffer.len()..476 {
-            buffer.push(0);
-        }
-        blocks.push(Uf2Block::allocate(base_addr, 0, num_blocks + 1, buffer));
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
 
-        // For each chunk, create
-        for chunk in chunks {
-            let mut data = chunk.to_vec().iter().map(|x| (**x)).collect::<Vec<u8>>();
-            for _ in data.len()..476 {
-                data.push(0);
+    if let Some(verify_path) = &args.verify {
+        let mut bytes = Vec::new();
+        File::open(verify_path)?.read_to_end(&mut bytes)?;
+
+        let (blocks, mut issues) = Uf2::parse(&bytes);
+        // The boot2 CRC only exists in the first block of images produced by
+        // the bootrom/progdata `create` flash layout (base address
+        // 0x10000000), not ELF- or bin_to_uf2-derived RP2040 images, which
+        // carry plain chunks with no embedded CRC. The check below only looks
+        // at the RP2040 family word and a first target_addr of 0x10000000, so
+        // an ELF- or bin_to_uf2-derived RP2040 image that starts at that
+        // address is CRC-checked as well.
+        let is_rp2040_boot2_image = blocks.first().is_some_and(|block| {
+            block.file_size == family::resolve("RP2040").unwrap() && block.target_addr == 0x10000000
+        });
+        if is_rp2040_boot2_image {
+            if let Some(crc_issue) = Uf2::verify_rp2040_crc(&blocks) {
+                issues.push(crc_issue);
             }
-
-            blocks.push(Uf2Block::allocate(
-                base_addr + blocks.len() as u32 * 256,
-                blocks.len() as u32,
-                num_blocks + 1,
-                data,
-            ));
         }
 
-        let block_count = blocks.len();
-        println!("{block_count} blocks generated");
-
-        return Uf2 { blocks: blocks };
-    }
-
-    pub fn as_bytes(&self) -> Vec<u8> {
-        let bytes = self.blocks.len() * 512;
-        let mut buf: Vec<u8> = Vec::with_capacity(bytes);
-
-        // For each chunk, write the bytes
-        // And remember it's all little endian
-        self.blocks.iter().for_each(|block| {
-            write_little_endian(&mut buf, block.magic_0);
-            write_little_endian(&mut buf, block.magic_1);
-            write_little_endian(&mut buf, block.flags);
-            write_little_endian(&mut buf, block.target_addr);
-            write_little_endian(&mut buf, block.payload_size);
-            write_little_endian(&mut buf, block.block_no);
-            write_little_endian(&mut buf, block.num_blocks);
-            write_little_endian(&mut buf, block.file_size);
-            let remaining = 476 - block.data.len();
-            buf.append(&mut block.data.clone());
-            for _ in 0..remaining {
-                buf.push(0u8);
+        if issues.is_empty() {
+            println!("{} blocks OK", blocks.len());
+        } else {
+            for issue in &issues {
+                println!("{issue}");
             }
-            write_little_endian(&mut buf, block.magic_end);
-        });
-
-        return buf;
+            std::process::exit(1);
+        }
+        return Ok(());
     }
-}
 
-fn main() -> std::io::Result<()> {
-    let args = Args::parse();
-
-    // Read the input file
-    let mut inp_file = File::open(args.bootrom)?;
-    let mut prog_file = File::open(args.progdata)?;
-
-    // Create a blank payload=
-    let mut data_buffer = Vec::new();
-    inp_file.read_to_end(&mut data_buffer);
+    let family_id = args
+        .family
+        .as_deref()
+        .map(family::resolve)
+        .transpose()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+
+    let mut uf2_file = if let Some(elf_path) = &args.elf {
+        let mut elf_bytes = Vec::new();
+        File::open(elf_path)?.read_to_end(&mut elf_bytes)?;
+
+        let segments = elf::load_segments(&elf_bytes)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        Uf2::from_elf_segments(segments, family_id)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?
+    } else {
+        // clap enforces bootrom/progdata via required_unless_present_any
+        // when neither --elf nor --verify is given.
+        let bootrom = args.bootrom.unwrap();
+        let progdata = args.progdata.unwrap();
+
+        // Read the input file
+        let mut inp_file = File::open(bootrom)?;
+        let mut prog_file = File::open(progdata)?;
+
+        // Create a blank payload=
+        let mut data_buffer = Vec::new();
+        inp_file.read_to_end(&mut data_buffer);
+
+        for _ in data_buffer.len()..256 {
+            data_buffer.push(0);
+        }
 
-    for _ in data_buffer.len()..256 {
-        data_buffer.push(0);
+        // Fill the program
+        prog_file.read_to_end(&mut data_buffer);
+
+        // Create the uf2
+        Uf2::create(&data_buffer.as_slice(), family_id)
+    };
+
+    if !args.embed.is_empty() {
+        let mut files = Vec::new();
+        for entry in &args.embed {
+            let (dest_path, src_file) = entry.split_once('=').ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("--embed value '{entry}' must be in dest_path=src_file form"),
+                )
+            })?;
+
+            let mut contents = Vec::new();
+            File::open(src_file)?.read_to_end(&mut contents)?;
+            files.push((dest_path.to_string(), contents));
+        }
+        uf2_file.append_embedded_files(files);
     }
 
-    // Fill the program
-    prog_file.read_to_end(&mut data_buffer);
-
-    // Create the uf2
-    let uf2_file = Uf2::create(&data_buffer.as_slice());
-
-    // Write the uf2
-    let mut file = File::create(args.output)?;
-    file.write(uf2_file.as_bytes().as_slice());
+    // Write the uf2, streamed directly to the output file.
+    // clap enforces --output via required_unless_present = "verify".
+    let output = args.output.unwrap();
+    let mut file = File::create(output)?;
+    uf2_file.write_to(&mut file)?;
     return Ok(());
 }